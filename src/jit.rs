@@ -0,0 +1,312 @@
+// Cranelift-based JIT backend: lowers an already-optimised `Chunk`
+// straight to native code and runs it, instead of walking the byte
+// stream with `run`. It reuses `decode_instr` to iterate the same
+// compiled representation `run` does, so every prior optimisation
+// pass applies equally to both backends.
+
+use std::io::Read as IoRead;
+use std::io::Write;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Linkage, Module};
+
+use crate::{decode_instr, Op};
+
+const TAPE_LEN: usize = 30000;
+
+extern "C" fn host_putch(byte: u8) {
+    print!("{}", byte as char);
+    let _ = std::io::stdout().flush();
+}
+
+extern "C" fn host_getch() -> u8 {
+    std::io::stdin()
+        .bytes()
+        .next()
+        .and_then(|b| b.ok())
+        .unwrap_or(0)
+}
+
+// Called from compiled code once an inline bounds check (see
+// `guard_addr`) has already established `offset` is outside
+// `[0, TAPE_LEN)`, to print the same message the interpreter's
+// `Tape::index` would for the equivalent overrun.
+extern "C" fn host_oob(offset: i64) {
+    if offset < 0 {
+        println!("ERROR: pointer ran off the start of the tape.");
+    } else {
+        println!("ERROR: pointer ran off the end of the tape.");
+    }
+}
+
+// Emit a guard in front of a tape access at the absolute pointer
+// `addr`: if it falls outside `[tape_base, tape_base + TAPE_LEN)`,
+// report it via `host_oob` and return from the compiled function
+// immediately, the same as `Tape::index` failing in the interpreter,
+// instead of letting the following load/store run off the tape.
+//
+// Leaves the builder positioned at the in-bounds continuation
+// block, so the caller can go on to emit the guarded load/store as
+// if this call weren't here.
+fn guard_addr(
+    builder: &mut FunctionBuilder,
+    base_var: Variable,
+    oob_ref: FuncRef,
+    exit_block: Block,
+    addr: Value,
+) {
+    let base = builder.use_var(base_var);
+    let offset = builder.ins().isub(addr, base);
+    let zero = builder.ins().iconst(types::I64, 0);
+    let len = builder.ins().iconst(types::I64, TAPE_LEN as i64);
+    let too_low = builder.ins().icmp(IntCC::SignedLessThan, offset, zero);
+    let too_high = builder.ins().icmp(IntCC::SignedGreaterThanOrEqual, offset, len);
+    let oob = builder.ins().bor(too_low, too_high);
+
+    let ok_block = builder.create_block();
+    let oob_block = builder.create_block();
+    builder.ins().brif(oob, oob_block, &[], ok_block, &[]);
+
+    builder.switch_to_block(oob_block);
+    builder.ins().call(oob_ref, &[offset]);
+    builder.ins().jump(exit_block, &[]);
+    builder.seal_block(oob_block);
+
+    builder.switch_to_block(ok_block);
+    builder.seal_block(ok_block);
+}
+
+// Lower `code` (a `Chunk`'s instruction bytes) to native code and
+// run it against a freshly allocated tape.
+pub fn run_jit(code: &[u8]) {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    flag_builder.set("is_pic", "false").unwrap();
+    let isa_builder = cranelift_native::builder().expect("host machine is not supported");
+    let isa = isa_builder
+        .finish(settings::Flags::new(flag_builder))
+        .expect("failed to build target ISA");
+
+    let mut jit_builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+    jit_builder.symbol("host_putch", host_putch as *const u8);
+    jit_builder.symbol("host_getch", host_getch as *const u8);
+    jit_builder.symbol("host_oob", host_oob as *const u8);
+    let mut module = JITModule::new(jit_builder);
+
+    let mut putch_sig = module.make_signature();
+    putch_sig.params.push(AbiParam::new(types::I8));
+    let putch_func = module
+        .declare_function("host_putch", Linkage::Import, &putch_sig)
+        .unwrap();
+
+    let mut getch_sig = module.make_signature();
+    getch_sig.returns.push(AbiParam::new(types::I8));
+    let getch_func = module
+        .declare_function("host_getch", Linkage::Import, &getch_sig)
+        .unwrap();
+
+    let mut oob_sig = module.make_signature();
+    oob_sig.params.push(AbiParam::new(types::I64));
+    let oob_func = module
+        .declare_function("host_oob", Linkage::Import, &oob_sig)
+        .unwrap();
+
+    let mut ctx = module.make_context();
+    ctx.func.signature.params.push(AbiParam::new(types::I64)); // tape base pointer
+
+    let program = module
+        .declare_function("program", Linkage::Export, &ctx.func.signature.clone())
+        .unwrap();
+
+    let mut fb_ctx = FunctionBuilderContext::new();
+    {
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut fb_ctx);
+
+        // One block per instruction boundary (plus one past the
+        // end), so that a jump can always target a block directly.
+        let mut boundaries = Vec::new();
+        let mut pos = 0;
+        while pos < code.len() {
+            boundaries.push(pos);
+            let (_, next) = decode_instr(code, pos);
+            pos = next;
+        }
+        boundaries.push(code.len());
+
+        let blocks: Vec<_> = boundaries.iter().map(|_| builder.create_block()).collect();
+        let block_at = |pos: usize| -> cranelift_codegen::ir::Block {
+            let idx = boundaries
+                .binary_search(&pos)
+                .expect("jump target is not an instruction boundary");
+            blocks[idx]
+        };
+
+        let entry = blocks[0];
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let tape_base = builder.block_params(entry)[0];
+        let dp = Variable::from_u32(0);
+        builder.declare_var(dp, types::I64);
+        builder.def_var(dp, tape_base);
+        // A separate variable (rather than reusing the entry
+        // block's `tape_base` value directly) so `guard_addr` can
+        // recover the tape's base address from any block.
+        let base_var = Variable::from_u32(1);
+        builder.declare_var(base_var, types::I64);
+        builder.def_var(base_var, tape_base);
+
+        let putch_ref = module.declare_func_in_func(putch_func, builder.func);
+        let getch_ref = module.declare_func_in_func(getch_func, builder.func);
+        let oob_ref = module.declare_func_in_func(oob_func, builder.func);
+
+        let exit = blocks[boundaries.len() - 1];
+
+        for (bi, &pos) in boundaries[..boundaries.len() - 1].iter().enumerate() {
+            let (instr, _) = decode_instr(code, pos);
+            builder.switch_to_block(blocks[bi]);
+            match instr.opcode {
+                Op::Add | Op::Sub => {
+                    let dp_val = builder.use_var(dp);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, dp_val);
+                    let cur = builder.ins().load(types::I8, MemFlags::new(), dp_val, 0);
+                    let delta = builder.ins().iconst(types::I8, instr.arg as i64);
+                    let result = if instr.opcode == Op::Add {
+                        builder.ins().iadd(cur, delta)
+                    } else {
+                        builder.ins().isub(cur, delta)
+                    };
+                    builder.ins().store(MemFlags::new(), result, dp_val, 0);
+                    builder.ins().jump(blocks[bi + 1], &[]);
+                }
+                Op::Set => {
+                    let dp_val = builder.use_var(dp);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, dp_val);
+                    let v = builder.ins().iconst(types::I8, instr.arg as i64);
+                    builder.ins().store(MemFlags::new(), v, dp_val, 0);
+                    builder.ins().jump(blocks[bi + 1], &[]);
+                }
+                Op::Left | Op::Right => {
+                    let dp_val = builder.use_var(dp);
+                    let delta = builder.ins().iconst(types::I64, instr.arg as i64);
+                    let new_dp = if instr.opcode == Op::Left {
+                        builder.ins().isub(dp_val, delta)
+                    } else {
+                        builder.ins().iadd(dp_val, delta)
+                    };
+                    builder.def_var(dp, new_dp);
+                    builder.ins().jump(blocks[bi + 1], &[]);
+                }
+                Op::PutCh => {
+                    let dp_val = builder.use_var(dp);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, dp_val);
+                    let cur = builder.ins().load(types::I8, MemFlags::new(), dp_val, 0);
+                    for _ in 0..instr.arg {
+                        builder.ins().call(putch_ref, &[cur]);
+                    }
+                    builder.ins().jump(blocks[bi + 1], &[]);
+                }
+                Op::GetCh => {
+                    let dp_val = builder.use_var(dp);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, dp_val);
+                    let mut got = None;
+                    for _ in 0..instr.arg {
+                        let call = builder.ins().call(getch_ref, &[]);
+                        got = Some(builder.inst_results(call)[0]);
+                    }
+                    if let Some(v) = got {
+                        builder.ins().store(MemFlags::new(), v, dp_val, 0);
+                    }
+                    builder.ins().jump(blocks[bi + 1], &[]);
+                }
+                Op::CMul | Op::CNMul => {
+                    let dp_val = builder.use_var(dp);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, dp_val);
+                    let cur = builder.ins().load(types::I8, MemFlags::new(), dp_val, 0);
+                    let factor = builder.ins().iconst(types::I8, instr.arg as i64);
+                    let product = builder.ins().imul(cur, factor);
+                    let tgt_off = builder.ins().iconst(types::I64, instr.off as i64);
+                    let tgt = builder.ins().iadd(dp_val, tgt_off);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, tgt);
+                    let tgt_val = builder.ins().load(types::I8, MemFlags::new(), tgt, 0);
+                    let new_val = if instr.opcode == Op::CMul {
+                        builder.ins().iadd(tgt_val, product)
+                    } else {
+                        builder.ins().isub(tgt_val, product)
+                    };
+                    builder.ins().store(MemFlags::new(), new_val, tgt, 0);
+                    builder.ins().jump(blocks[bi + 1], &[]);
+                }
+                Op::SeekL | Op::SeekR => {
+                    let test_block = builder.create_block();
+                    let body_block = builder.create_block();
+                    builder.ins().jump(test_block, &[]);
+
+                    builder.switch_to_block(test_block);
+                    let dp_val = builder.use_var(dp);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, dp_val);
+                    let cur = builder.ins().load(types::I8, MemFlags::new(), dp_val, 0);
+                    builder
+                        .ins()
+                        .brif(cur, body_block, &[], blocks[bi + 1], &[]);
+
+                    builder.switch_to_block(body_block);
+                    let step = builder.ins().iconst(types::I64, instr.arg as i64);
+                    let shifted = if instr.opcode == Op::SeekL {
+                        builder.ins().isub(dp_val, step)
+                    } else {
+                        builder.ins().iadd(dp_val, step)
+                    };
+                    builder.def_var(dp, shifted);
+                    builder.ins().jump(test_block, &[]);
+
+                    builder.seal_block(test_block);
+                    builder.seal_block(body_block);
+                }
+                Op::J => {
+                    let target = (pos as i32 + instr.off) as usize;
+                    builder.ins().jump(block_at(target), &[]);
+                }
+                Op::JZ => {
+                    let target = (pos as i32 + instr.off) as usize;
+                    let dp_val = builder.use_var(dp);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, dp_val);
+                    let cur = builder.ins().load(types::I8, MemFlags::new(), dp_val, 0);
+                    builder
+                        .ins()
+                        .brif(cur, blocks[bi + 1], &[], block_at(target), &[]);
+                }
+                Op::JNZ => {
+                    let target = (pos as i32 + instr.off) as usize;
+                    let dp_val = builder.use_var(dp);
+                    guard_addr(&mut builder, base_var, oob_ref, exit, dp_val);
+                    let cur = builder.ins().load(types::I8, MemFlags::new(), dp_val, 0);
+                    builder
+                        .ins()
+                        .brif(cur, block_at(target), &[], blocks[bi + 1], &[]);
+                }
+            }
+        }
+
+        builder.switch_to_block(exit);
+        builder.ins().return_(&[]);
+
+        for block in &blocks {
+            builder.seal_block(*block);
+        }
+
+        builder.finalize();
+    }
+
+    module.define_function(program, &mut ctx).unwrap();
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().unwrap();
+
+    let code_ptr = module.get_finalized_function(program);
+    let mut tape = vec![0u8; TAPE_LEN];
+    let entry_fn = unsafe { std::mem::transmute::<*const u8, extern "C" fn(*mut u8)>(code_ptr) };
+    entry_fn(tape.as_mut_ptr());
+}
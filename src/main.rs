@@ -1,8 +1,11 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
+mod jit;
+
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 struct Options {
     // Fuse adjacent equal operations.
@@ -19,25 +22,154 @@ struct Options {
     // Use a set before the sart or end of a loop to change the
     // condition.
     loop_set_jump: bool,
+    // Track statically-known cell values across straight-line code
+    // and use them to fold arithmetic, drop redundant sets, and
+    // delete loops which are provably never entered.
+    fold_constants: bool,
+}
+
+// How `Op::GetCh` should behave once the input is exhausted, since
+// brainfuck programs in the wild disagree about this.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum EofMode {
+    // Leave the cell holding whatever it held before the read.
+    Unchanged,
+    // Set the cell to 0.
+    Zero,
+    // Set the cell to all-ones (0xff, 0xffff, or 0xffffffff,
+    // depending on the configured cell width).
+    NegOne,
+}
+
+// The width of a single tape cell, and hence the wraparound point
+// for its arithmetic.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum CellWidth {
+    W8,
+    W16,
+    W32,
+}
+
+// Runtime configuration of the interpreter's memory, exposed via
+// CLI flags. Unlike `Options`, this mostly governs how `run`'s tape
+// behaves; the one effect on compilation is that a cell width other
+// than 8 bits turns off the 8-bit-only folds (see `main`).
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct MemoryConfig {
+    // Grow the tape rightward on out-of-range access, instead of
+    // treating it as an error.
+    grow_right: bool,
+    // Grow the tape leftward on out-of-range access, instead of
+    // treating it as an error. Requires the data pointer to be
+    // signed. When this is off, `Left` stops at cell 0 instead, so
+    // only a `SeekL` can run off the start.
+    grow_left: bool,
+    cell_width: CellWidth,
+    eof_mode: EofMode,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        MemoryConfig {
+            grow_right: false,
+            grow_left: false,
+            cell_width: CellWidth::W8,
+            eof_mode: EofMode::Unchanged,
+        }
+    }
 }
 
 fn main() {
-    let opts = Options {
+    let mut opts = Options {
         fuse_adjacent: true,
         fuse_set_add: true,
         loop_set_zero: true,
         loop_copy_multiply: true,
         loop_seek_lr: false,
         loop_set_jump: true,
+        fold_constants: true,
     };
 
 
-    if let Some(fname) = env::args().nth(1) {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut use_jit = false;
+    let mut disassemble_only = false;
+    let mut profile = false;
+    let mut mem_config = MemoryConfig::default();
+    let mut fname = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--jit" => use_jit = true,
+            "--disassemble" => disassemble_only = true,
+            "--profile" => profile = true,
+            "--grow-right" => mem_config.grow_right = true,
+            "--grow-left" => mem_config.grow_left = true,
+            "--cell-width" => {
+                i += 1;
+                match args.get(i).map(String::as_str) {
+                    Some("8") => mem_config.cell_width = CellWidth::W8,
+                    Some("16") => mem_config.cell_width = CellWidth::W16,
+                    Some("32") => mem_config.cell_width = CellWidth::W32,
+                    _ => {
+                        println!("ERROR: --cell-width must be 8, 16, or 32.");
+                        return;
+                    }
+                }
+            }
+            "--eof" => {
+                i += 1;
+                match args.get(i).map(String::as_str) {
+                    Some("unchanged") => mem_config.eof_mode = EofMode::Unchanged,
+                    Some("zero") => mem_config.eof_mode = EofMode::Zero,
+                    Some("0xff") => mem_config.eof_mode = EofMode::NegOne,
+                    _ => {
+                        println!("ERROR: --eof must be unchanged, zero, or 0xff.");
+                        return;
+                    }
+                }
+            }
+            other => fname = Some(other.to_string()),
+        }
+        i += 1;
+    }
+
+    // `fuse_set_add` and `fold_constants` both fold `Add`/`Sub` into a
+    // `Set` by doing the arithmetic themselves, using `u8::wrapping_*`
+    // on the IR's byte-sized operand -- that's only the same thing
+    // the tape would compute if cells really are 8 bits wide. For a
+    // wider configured cell, skip both folds and leave the `Add`/`Sub`
+    // in place, so `run`'s `Cell`-parameterized arithmetic (which
+    // wraps at the configured width) is what actually produces the
+    // value, instead of a fold that's already baked in 8-bit wrap.
+    if mem_config.cell_width != CellWidth::W8 {
+        opts.fuse_set_add = false;
+        opts.fold_constants = false;
+    }
+
+    if let Some(fname) = fname {
         if let Ok(mut file) = File::open(Path::new(&fname)) {
             let mut code = String::new();
             if file.read_to_string(&mut code).is_ok() {
                 if let Some(compiled) = compile(code, opts) {
-                    run(compiled);
+                    let instrs = fold_constants(compiled, opts);
+                    let chunk = encode_chunk(&instrs);
+                    if disassemble_only {
+                        disassemble(&chunk);
+                    } else if use_jit {
+                        // The JIT backend always uses the classic
+                        // fixed-size 8-bit tape; the memory-model
+                        // and profiling flags only apply to the
+                        // interpreter.
+                        jit::run_jit(&chunk.code);
+                    } else {
+                        match mem_config.cell_width {
+                            CellWidth::W8 => run::<u8>(chunk, mem_config, profile),
+                            CellWidth::W16 => run::<u16>(chunk, mem_config, profile),
+                            CellWidth::W32 => run::<u32>(chunk, mem_config, profile),
+                        }
+                    }
                 } else {
                     println!("ERROR: could not compile code (are your brackets matched?");
                 }
@@ -46,7 +178,9 @@ fn main() {
             println!("ERROR: could not open file.");
         }
     } else {
-        println!("USAGE: bfo <file>");
+        println!(
+            "USAGE: bfo [--jit] [--disassemble] [--profile] [--grow-right] [--grow-left] [--cell-width 8|16|32] [--eof unchanged|zero|0xff] <file>"
+        );
     }
 }
 
@@ -89,6 +223,224 @@ fn opcode(c: char) -> Option<Op> {
     }
 }
 
+fn op_to_byte(op: Op) -> u8 {
+    op as u8
+}
+
+fn op_from_byte(b: u8) -> Op {
+    match b {
+        x if x == Op::Add as u8 => Op::Add,
+        x if x == Op::Sub as u8 => Op::Sub,
+        x if x == Op::Left as u8 => Op::Left,
+        x if x == Op::Right as u8 => Op::Right,
+        x if x == Op::PutCh as u8 => Op::PutCh,
+        x if x == Op::GetCh as u8 => Op::GetCh,
+        x if x == Op::J as u8 => Op::J,
+        x if x == Op::JZ as u8 => Op::JZ,
+        x if x == Op::JNZ as u8 => Op::JNZ,
+        x if x == Op::Set as u8 => Op::Set,
+        x if x == Op::CMul as u8 => Op::CMul,
+        x if x == Op::CNMul as u8 => Op::CNMul,
+        x if x == Op::SeekL as u8 => Op::SeekL,
+        x if x == Op::SeekR as u8 => Op::SeekR,
+        _ => panic!("Unknown opcode byte: {}", b),
+    }
+}
+
+// The compiled, executable form of a program: a dense stream of
+// single-byte opcodes with packed variable-length operands, rather
+// than a `Vec<Instr>` of fixed (and mostly wasted) 8-byte records.
+// `Add`/`Sub`/`Left`/`Right`/`Set`/`PutCh`/`GetCh` carry one operand
+// byte; `CMul`/`CNMul` carry a factor byte plus a varint-encoded
+// signed cell offset; `J`/`JZ`/`JNZ` carry a fixed 4-byte relative
+// jump target (in bytes, from the start of the jump instruction);
+// `SeekL`/`SeekR` carry a single stride byte.
+struct Chunk {
+    code: Vec<u8>,
+}
+
+// Zigzag-encode a signed offset so small magnitudes (the common
+// case) take few bytes, then LEB128-encode the result.
+fn push_varint(code: &mut Vec<u8>, v: i32) {
+    let mut z = ((v << 1) ^ (v >> 31)) as u32;
+    loop {
+        let byte = (z & 0x7f) as u8;
+        z >>= 7;
+        if z != 0 {
+            code.push(byte | 0x80);
+        } else {
+            code.push(byte);
+            break;
+        }
+    }
+}
+
+fn varint_len(v: i32) -> usize {
+    let mut z = ((v << 1) ^ (v >> 31)) as u32;
+    let mut len = 1;
+    while z > 0x7f {
+        z >>= 7;
+        len += 1;
+    }
+    len
+}
+
+fn read_varint(code: &[u8], pos: usize) -> (i32, usize) {
+    let mut result: u32 = 0;
+    let mut shift = 0;
+    let mut p = pos;
+    loop {
+        let byte = code[p];
+        p += 1;
+        result |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    let v = ((result >> 1) as i32) ^ -((result & 1) as i32);
+    (v, p)
+}
+
+// The number of bytes `instr` will occupy once encoded, without
+// actually encoding it. Used to work out where every instruction
+// lands before any jump targets (which depend on those positions)
+// are written.
+fn instr_byte_len(instr: &Instr) -> usize {
+    match instr.opcode {
+        Op::Add | Op::Sub | Op::Left | Op::Right | Op::PutCh | Op::GetCh | Op::Set => 2,
+        Op::CMul | Op::CNMul => 2 + varint_len(instr.off),
+        Op::J | Op::JZ | Op::JNZ => 5,
+        Op::SeekL | Op::SeekR => 2,
+    }
+}
+
+fn encode_instr(code: &mut Vec<u8>, instr: Instr) {
+    code.push(op_to_byte(instr.opcode));
+    match instr.opcode {
+        Op::Add | Op::Sub | Op::Left | Op::Right | Op::PutCh | Op::GetCh | Op::Set => {
+            code.push(instr.arg);
+        }
+        Op::CMul | Op::CNMul => {
+            code.push(instr.arg);
+            push_varint(code, instr.off);
+        }
+        Op::J | Op::JZ | Op::JNZ => {
+            code.extend_from_slice(&instr.off.to_le_bytes());
+        }
+        Op::SeekL | Op::SeekR => {
+            code.push(instr.arg);
+        }
+    }
+}
+
+// Decode the instruction starting at `pos`, returning it along with
+// the position of the instruction which follows it.
+fn decode_instr(code: &[u8], pos: usize) -> (Instr, usize) {
+    let opcode = op_from_byte(code[pos]);
+    let mut p = pos + 1;
+    let instr = match opcode {
+        Op::Add | Op::Sub | Op::Left | Op::Right | Op::PutCh | Op::GetCh | Op::Set => {
+            let arg = code[p];
+            p += 1;
+            Instr { opcode, arg, off: 0 }
+        }
+        Op::CMul | Op::CNMul => {
+            let arg = code[p];
+            p += 1;
+            let (off, np) = read_varint(code, p);
+            p = np;
+            Instr { opcode, arg, off }
+        }
+        Op::J | Op::JZ | Op::JNZ => {
+            let off = i32::from_le_bytes([code[p], code[p + 1], code[p + 2], code[p + 3]]);
+            p += 4;
+            Instr { opcode, arg: 0, off }
+        }
+        Op::SeekL | Op::SeekR => {
+            let arg = code[p];
+            p += 1;
+            Instr { opcode, arg, off: 0 }
+        }
+    };
+    (instr, p)
+}
+
+// Re-decode a whole chunk back into `Instr`s, e.g. for a
+// disassembler, or for a pass which wants to inspect or rewrite
+// already-compiled code. `off` fields are left as the byte-relative
+// jump targets `Chunk` itself uses, not instruction counts.
+fn decode_chunk(chunk: &Chunk) -> Vec<Instr> {
+    let mut instrs = Vec::new();
+    let mut pos = 0;
+    while pos < chunk.code.len() {
+        let (instr, next) = decode_instr(&chunk.code, pos);
+        instrs.push(instr);
+        pos = next;
+    }
+    instrs
+}
+
+// Print the fully-optimised instruction stream in a readable
+// textual form, one line per `Instr`: its byte position, opcode,
+// arg, and (for jumps) the absolute byte position it targets. This
+// is the same representation `run` and the JIT backend execute, so
+// it's the ground truth for debugging a mis-optimisation.
+fn disassemble(chunk: &Chunk) {
+    let mut pos = 0;
+    for instr in decode_chunk(chunk) {
+        let len = instr_byte_len(&instr);
+        match instr.opcode {
+            Op::J | Op::JZ | Op::JNZ => {
+                let target = pos as i32 + instr.off;
+                println!("{:6}  {:?} {} -> {}", pos, instr.opcode, instr.arg, target);
+            }
+            Op::CMul | Op::CNMul => {
+                println!("{:6}  {:?} {} @{:+}", pos, instr.opcode, instr.arg, instr.off);
+            }
+            _ => {
+                println!("{:6}  {:?} {}", pos, instr.opcode, instr.arg);
+            }
+        }
+        pos += len;
+    }
+}
+
+// Pack a finished, optimised instruction list into a `Chunk`. Jump
+// `off`s in `instrs` are instruction-index deltas (as produced by
+// `compile`/`optimise_loop`/`fold_constants`); here they get
+// translated into the byte-relative deltas the interpreter actually
+// uses, once every instruction's final byte position is known.
+fn encode_chunk(instrs: &[Instr]) -> Chunk {
+    let n = instrs.len();
+    let mut starts = vec![0usize; n + 1];
+    let mut pos = 0;
+    for (i, instr) in instrs.iter().enumerate() {
+        starts[i] = pos;
+        pos += instr_byte_len(instr);
+    }
+    starts[n] = pos;
+
+    let mut code = Vec::with_capacity(pos);
+    for (i, instr) in instrs.iter().enumerate() {
+        let to_encode = match instr.opcode {
+            Op::J | Op::JZ | Op::JNZ => {
+                // `+ 1` because the old instruction-indexed `off`,
+                // once taken, always fell through to the following
+                // instruction too.
+                let target = (i as i32 + instr.off + 1) as usize;
+                Instr {
+                    off: starts[target] as i32 - starts[i] as i32,
+                    ..*instr
+                }
+            }
+            _ => *instr,
+        };
+        encode_instr(&mut code, to_encode);
+    }
+    Chunk { code }
+}
+
 fn compile(code: String, opts: Options) -> Option<Vec<Instr>> {
     let mut instrs = Vec::new();
     let mut jumps = Vec::new();
@@ -268,34 +620,35 @@ fn optimise_loop(code: &Vec<Instr>, start: usize, opts: Options) -> Option<Vec<I
         Some(instrs)
     };
 
-    // Replace [<] and [>] with a single "seek left" or "seek right"
-    // operation.
+    // Replace a loop which does nothing but shift the data pointer
+    // by a constant stride (e.g. `[<]`, `[>>>]`, `[><<]`) with a
+    // single "seek left" or "seek right" operation encoding that
+    // stride, which scans until it finds a zero cell.
     let seek_lr = || {
         if !opts.loop_seek_lr {
             return None;
         }
 
-        if code.len() != start + 3 {
-            return None;
+        let mut stride: i32 = 0;
+        for i in start + 1..code.len() - 1 {
+            match code[i].opcode {
+                Op::Left => stride -= code[i].arg as i32,
+                Op::Right => stride += code[i].arg as i32,
+                _ => return None,
+            }
         }
 
-        match code[start + 1].opcode {
-            Op::Left if code[start + 1].arg == 1 => {
-                Some(vec![Instr {
-                              opcode: Op::SeekL,
-                              arg: 0,
-                              off: 0,
-                          }])
-            }
-            Op::Right if code[start + 1].arg == 1 => {
-                Some(vec![Instr {
-                              opcode: Op::SeekR,
-                              arg: 0,
-                              off: 0,
-                          }])
-            }
-            _ => None,
+        let magnitude = stride.unsigned_abs();
+        if magnitude == 0 || magnitude > u8::MAX as u32 {
+            return None;
         }
+
+        let opcode = if stride < 0 { Op::SeekL } else { Op::SeekR };
+        Some(vec![Instr {
+            opcode,
+            arg: magnitude as u8,
+            off: 0,
+        }])
     };
 
     // Turn a set followed by a conditional jump into a set followed
@@ -305,9 +658,8 @@ fn optimise_loop(code: &Vec<Instr>, start: usize, opts: Options) -> Option<Vec<I
             return None;
         }
 
-        let before1 = code[start - 1];
         let before2 = code[code.len() - 2];
-        if start > 0 && before1.opcode == Op::Set && before1.arg == 0 {
+        if start > 0 && code[start - 1].opcode == Op::Set && code[start - 1].arg == 0 {
             // Loop opener is JZ, so Set 0; [ ... ] ==> Set 0.
             Some(vec![])
         } else if before2.opcode == Op::Set {
@@ -343,78 +695,504 @@ fn optimise_loop(code: &Vec<Instr>, start: usize, opts: Options) -> Option<Vec<I
     set_zero().or(copy_multiply().or(seek_lr().or(set_jump())))
 }
 
-fn run(code: Vec<Instr>) {
-    let mut ip = 0;
-    let mut memory: [u8; 30000] = [0; 30000];
-    let mut dp = 0;
+// The statically-known value of a cell relative to the current data
+// pointer, as tracked by `fold_constants`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum CellValue {
+    Known(u8),
+    NonZero,
+}
+
+// Whole-program constant-folding pass. Walks the flat instruction
+// stream, tracking which cells (relative to the current data
+// pointer) have a statically-known value, so that:
+//
+//   - `Add`/`Sub` against a known cell fold into a `Set`;
+//   - a `Set` to an already-known value is redundant and dropped;
+//   - a loop whose current cell is known to be zero on entry can
+//     never run, so the whole loop is deleted.
+//
+// Knowledge only holds within a straight-line region: entering a
+// loop body, or falling through one, discards everything we thought
+// we knew, since we don't reason about how many times a loop runs.
+fn fold_constants(code: Vec<Instr>, opts: Options) -> Vec<Instr> {
+    if !opts.fold_constants {
+        return code;
+    }
+
+    let n = code.len();
+    let mut out: Vec<Instr> = Vec::with_capacity(n);
+    // For jump instructions, the absolute old-code index their
+    // `off` was pointing at, so it can be recomputed once the new
+    // instruction stream (and hence its length) is known.
+    let mut jump_targets: Vec<Option<usize>> = Vec::with_capacity(n);
+    // Maps an old instruction index to the index it ends up at (or
+    // would have ended up at, if it was deleted) in `out`.
+    let mut index_map = vec![0usize; n + 1];
+    let mut cells: HashMap<i32, CellValue> = HashMap::new();
+    let mut offset: i32 = 0;
 
-    while ip < code.len() {
-        let instr = code[ip];
+    let mut i = 0;
+    while i < n {
+        index_map[i] = out.len();
+        let instr = code[i];
         match instr.opcode {
+            Op::Set => {
+                if cells.get(&offset) == Some(&CellValue::Known(instr.arg)) {
+                    i += 1;
+                    continue;
+                }
+                cells.insert(offset, CellValue::Known(instr.arg));
+                out.push(instr);
+                jump_targets.push(None);
+            }
             Op::Add => {
-                memory[dp] = memory[dp].wrapping_add(instr.arg);
+                match cells.get(&offset) {
+                    Some(&CellValue::Known(v)) => {
+                        let folded = v.wrapping_add(instr.arg);
+                        cells.insert(offset, CellValue::Known(folded));
+                        out.push(Instr {
+                            opcode: Op::Set,
+                            arg: folded,
+                            off: 0,
+                        });
+                    }
+                    _ => {
+                        cells.remove(&offset);
+                        out.push(instr);
+                    }
+                }
+                jump_targets.push(None);
             }
             Op::Sub => {
-                memory[dp] = memory[dp].wrapping_sub(instr.arg);
+                match cells.get(&offset) {
+                    Some(&CellValue::Known(v)) => {
+                        let folded = v.wrapping_sub(instr.arg);
+                        cells.insert(offset, CellValue::Known(folded));
+                        out.push(Instr {
+                            opcode: Op::Set,
+                            arg: folded,
+                            off: 0,
+                        });
+                    }
+                    _ => {
+                        cells.remove(&offset);
+                        out.push(instr);
+                    }
+                }
+                jump_targets.push(None);
             }
             Op::Left => {
-                dp = dp.saturating_sub(instr.arg as usize);
+                offset -= instr.arg as i32;
+                out.push(instr);
+                jump_targets.push(None);
             }
             Op::Right => {
-                dp = dp.saturating_add(instr.arg as usize);
+                offset += instr.arg as i32;
+                out.push(instr);
+                jump_targets.push(None);
             }
             Op::PutCh => {
-                for _ in 0..instr.arg {
-                    print!("{}", memory[dp] as char);
-                }
+                out.push(instr);
+                jump_targets.push(None);
             }
             Op::GetCh => {
-                // Only the last character input will be kept, but
-                // only asking for one character would change the
-                // program semantics.
-                for _ in 0..instr.arg {
-                    let inp: Option<u8> = std::io::stdin()
-                        .bytes()
-                        .next()
-                        .and_then(|result| result.ok());
-                    if let Some(inp_u8) = inp {
-                        memory[dp] = inp_u8;
-                    }
-                }
+                cells.remove(&offset);
+                out.push(instr);
+                jump_targets.push(None);
+            }
+            Op::CMul | Op::CNMul => {
+                cells.remove(&(offset + instr.off));
+                out.push(instr);
+                jump_targets.push(None);
+            }
+            Op::SeekL | Op::SeekR => {
+                cells.clear();
+                out.push(instr);
+                jump_targets.push(None);
             }
-            Op::J => ip = (ip as i32 + instr.off) as usize,
             Op::JZ => {
-                if memory[dp] == 0 {
-                    ip = (ip as i32 + instr.off) as usize
+                // `target` is the absolute old index this jump lands
+                // on when the current cell is zero, i.e. the last
+                // instruction of the loop.
+                let target = (i as i32 + instr.off) as usize;
+                if cells.get(&offset) == Some(&CellValue::Known(0)) {
+                    // The loop can never run: delete it entirely.
+                    for entry in index_map.iter_mut().take(target + 1).skip(i) {
+                        *entry = out.len();
+                    }
+                    i = target;
+                } else {
+                    // The loop may run, so we know nothing about
+                    // what it leaves behind, except that the current
+                    // cell is nonzero (or we wouldn't have entered).
+                    cells.clear();
+                    cells.insert(offset, CellValue::NonZero);
+                    out.push(instr);
+                    jump_targets.push(Some(target));
                 }
             }
-            Op::JNZ => {
-                if memory[dp] != 0 {
-                    ip = (ip as i32 + instr.off) as usize
-                }
+            Op::JNZ | Op::J => {
+                let target = (i as i32 + instr.off) as usize;
+                cells.clear();
+                out.push(instr);
+                jump_targets.push(Some(target));
             }
-            Op::Set => {
-                memory[dp] = instr.arg;
+        }
+        i += 1;
+    }
+    index_map[n] = out.len();
+
+    for (idx, target) in jump_targets.into_iter().enumerate() {
+        if let Some(old_target) = target {
+            out[idx].off = index_map[old_target] as i32 - idx as i32;
+        }
+    }
+
+    out
+}
+
+// A single tape cell's width-dependent arithmetic, so `run` can be
+// written once and instantiated over `u8`/`u16`/`u32`.
+trait Cell: Copy + Default + PartialEq + From<u8> {
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    fn wrapping_mul(self, rhs: Self) -> Self;
+    // Truncated to a byte for `PutCh`, which always deals in chars.
+    fn as_u8(self) -> u8;
+    // The all-ones value, for `EofMode::NegOne`.
+    fn all_ones() -> Self;
+}
+
+macro_rules! impl_cell {
+    ($ty:ty, $all_ones:expr) => {
+        impl Cell for $ty {
+            fn wrapping_add(self, rhs: Self) -> Self {
+                <$ty>::wrapping_add(self, rhs)
             }
-            Op::CMul => {
-                let tgt = (dp as i32 + instr.off) as usize;
-                memory[tgt] = memory[tgt].wrapping_add(memory[dp].wrapping_mul(instr.arg));
+            fn wrapping_sub(self, rhs: Self) -> Self {
+                <$ty>::wrapping_sub(self, rhs)
             }
-            Op::CNMul => {
-                let tgt = (dp as i32 + instr.off) as usize;
-                memory[tgt] = memory[tgt].wrapping_sub(memory[dp].wrapping_mul(instr.arg));
+            fn wrapping_mul(self, rhs: Self) -> Self {
+                <$ty>::wrapping_mul(self, rhs)
             }
-            Op::SeekL => {
-                while memory[dp] > 0 {
-                    dp -= 1;
+            fn as_u8(self) -> u8 {
+                self as u8
+            }
+            fn all_ones() -> Self {
+                $all_ones
+            }
+        }
+    };
+}
+
+impl_cell!(u8, 0xff);
+impl_cell!(u16, 0xffff);
+impl_cell!(u32, 0xffff_ffff);
+
+// The interpreter's data memory: a band of cells addressed by a
+// signed logical index. If configured, the band can grow to the
+// right (the common case) and to the left, rather than the fixed
+// `[u8; 30000]` this used to be.
+struct Tape<C> {
+    cells: Vec<C>,
+    // The logical index of `cells[0]`. Only ever moves away from 0
+    // once left-growth has shifted the whole band.
+    base: isize,
+    config: MemoryConfig,
+}
+
+impl<C: Cell> Tape<C> {
+    fn new(config: MemoryConfig) -> Self {
+        Tape {
+            cells: vec![C::default(); 30000],
+            base: 0,
+            config,
+        }
+    }
+
+    // Translate a logical index into a position in `cells`, growing
+    // the band (if configured to) when the index falls outside it,
+    // or else reporting the overrun as an error.
+    fn index(&mut self, logical: isize) -> Result<usize, String> {
+        if logical < self.base {
+            if !self.config.grow_left {
+                return Err("ERROR: pointer ran off the start of the tape.".to_string());
+            }
+            let extra = (self.base - logical) as usize;
+            let mut grown = vec![C::default(); extra];
+            grown.extend_from_slice(&self.cells);
+            self.cells = grown;
+            self.base -= extra as isize;
+            Ok(0)
+        } else {
+            let idx = (logical - self.base) as usize;
+            if idx >= self.cells.len() {
+                if !self.config.grow_right {
+                    return Err("ERROR: pointer ran off the end of the tape.".to_string());
                 }
+                self.cells.resize(idx + 1, C::default());
+            }
+            Ok(idx)
+        }
+    }
+
+    fn get(&mut self, logical: isize) -> Result<C, String> {
+        let idx = self.index(logical)?;
+        Ok(self.cells[idx])
+    }
+
+    fn cell_mut(&mut self, logical: isize) -> Result<&mut C, String> {
+        let idx = self.index(logical)?;
+        Ok(&mut self.cells[idx])
+    }
+}
+
+fn run<C: Cell>(chunk: Chunk, mem_config: MemoryConfig, profile: bool) {
+    let code = &chunk.code;
+    let mut tape: Tape<C> = Tape::new(mem_config);
+    // Per-instruction execution counts, indexed by byte position.
+    // Left `None` unless `--profile` is given, so the ordinary run
+    // path allocates and updates no counts.
+    let mut counts: Option<Vec<u64>> = if profile { Some(vec![0; code.len()]) } else { None };
+
+    // The actual execution loop, pulled into a closure so a
+    // mid-program tape error can bail out with `?` instead of every
+    // opcode needing its own early-return plumbing.
+    let result: Result<(), String> = (|| {
+        let mut ip = 0;
+        let mut dp: isize = 0;
+        while ip < code.len() {
+            if let Some(counts) = counts.as_mut() {
+                counts[ip] += 1;
             }
-            Op::SeekR => {
-                while memory[dp] > 0 {
-                    dp += 1;
+            let (instr, next_ip) = decode_instr(code, ip);
+            match instr.opcode {
+                Op::Add => {
+                    let c = tape.cell_mut(dp)?;
+                    *c = c.wrapping_add(C::from(instr.arg));
+                    ip = next_ip;
+                }
+                Op::Sub => {
+                    let c = tape.cell_mut(dp)?;
+                    *c = c.wrapping_sub(C::from(instr.arg));
+                    ip = next_ip;
+                }
+                Op::Left => {
+                    dp = if mem_config.grow_left {
+                        dp - instr.arg as isize
+                    } else {
+                        (dp - instr.arg as isize).max(0)
+                    };
+                    ip = next_ip;
+                }
+                Op::Right => {
+                    dp += instr.arg as isize;
+                    ip = next_ip;
+                }
+                Op::PutCh => {
+                    let v = tape.get(dp)?;
+                    for _ in 0..instr.arg {
+                        print!("{}", v.as_u8() as char);
+                    }
+                    ip = next_ip;
+                }
+                Op::GetCh => {
+                    // Only the last character input will be kept, but
+                    // only asking for one character would change the
+                    // program semantics.
+                    for _ in 0..instr.arg {
+                        let inp: Option<u8> = std::io::stdin()
+                            .bytes()
+                            .next()
+                            .and_then(|result| result.ok());
+                        let c = tape.cell_mut(dp)?;
+                        *c = match inp {
+                            Some(inp_u8) => C::from(inp_u8),
+                            None => match mem_config.eof_mode {
+                                EofMode::Unchanged => *c,
+                                EofMode::Zero => C::default(),
+                                EofMode::NegOne => C::all_ones(),
+                            },
+                        };
+                    }
+                    ip = next_ip;
+                }
+                // Unlike the other opcodes, a jump's `off` is already
+                // a byte-relative target, not a delta from `next_ip`.
+                Op::J => ip = (ip as i32 + instr.off) as usize,
+                Op::JZ => {
+                    let v = tape.get(dp)?;
+                    ip = if v == C::default() {
+                        (ip as i32 + instr.off) as usize
+                    } else {
+                        next_ip
+                    }
+                }
+                Op::JNZ => {
+                    let v = tape.get(dp)?;
+                    ip = if v != C::default() {
+                        (ip as i32 + instr.off) as usize
+                    } else {
+                        next_ip
+                    }
+                }
+                Op::Set => {
+                    let c = tape.cell_mut(dp)?;
+                    *c = C::from(instr.arg);
+                    ip = next_ip;
+                }
+                Op::CMul => {
+                    let v = tape.get(dp)?;
+                    let tgt = dp + instr.off as isize;
+                    let c = tape.cell_mut(tgt)?;
+                    *c = c.wrapping_add(v.wrapping_mul(C::from(instr.arg)));
+                    ip = next_ip;
+                }
+                Op::CNMul => {
+                    let v = tape.get(dp)?;
+                    let tgt = dp + instr.off as isize;
+                    let c = tape.cell_mut(tgt)?;
+                    *c = c.wrapping_sub(v.wrapping_mul(C::from(instr.arg)));
+                    ip = next_ip;
+                }
+                Op::SeekL => {
+                    let stride = instr.arg as isize;
+                    while tape.get(dp)? != C::default() {
+                        dp -= stride;
+                    }
+                    ip = next_ip;
+                }
+                Op::SeekR => {
+                    let stride = instr.arg as isize;
+                    while tape.get(dp)? != C::default() {
+                        dp += stride;
+                    }
+                    ip = next_ip;
                 }
             }
         }
-        ip += 1;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+    if let Some(counts) = counts {
+        report_profile(&chunk, &counts);
+    }
+}
+
+// Print the most-executed instructions recorded by `--profile`, to
+// help find loops an optimisation pass failed to collapse. Byte
+// positions that never ran (dead code, or folded away already) are
+// omitted.
+fn report_profile(chunk: &Chunk, counts: &[u64]) {
+    let mut hottest: Vec<(usize, u64)> = counts
+        .iter()
+        .enumerate()
+        .filter(|&(_, &count)| count > 0)
+        .map(|(pos, &count)| (pos, count))
+        .collect();
+    hottest.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+    println!("--- profile: {} instructions executed at least once ---", hottest.len());
+    for (pos, count) in hottest.iter().take(20) {
+        let (instr, _) = decode_instr(&chunk.code, *pos);
+        println!("{:>12}  {:6}  {:?} {}", count, pos, instr.opcode, instr.arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts() -> Options {
+        Options {
+            fuse_adjacent: true,
+            fuse_set_add: true,
+            loop_set_zero: true,
+            loop_copy_multiply: true,
+            loop_seek_lr: true,
+            loop_set_jump: true,
+            fold_constants: true,
+        }
+    }
+
+    #[test]
+    fn encode_decode_chunk_roundtrip() {
+        let instrs = compile("++>+++[-]<.".to_string(), opts()).unwrap();
+        let chunk = encode_chunk(&instrs);
+        assert_eq!(decode_chunk(&chunk), instrs);
+    }
+
+    #[test]
+    fn set_zero_loop_collapses_to_set() {
+        // `[-]` always leaves the cell at zero, however many times it
+        // runs, so the loop compiles straight to a single `Set 0`.
+        let instrs = compile("[-]".to_string(), opts()).unwrap();
+        assert_eq!(instrs, vec![Instr { opcode: Op::Set, arg: 0, off: 0 }]);
+    }
+
+    #[test]
+    fn seek_loop_collapses_to_single_instruction() {
+        let instrs = compile(">>>[>>>]".to_string(), opts()).unwrap();
+        assert!(instrs.iter().any(|i| i.opcode == Op::SeekR && i.arg == 3));
+        assert!(!instrs.iter().any(|i| i.opcode == Op::JZ || i.opcode == Op::JNZ));
+    }
+
+    #[test]
+    fn fold_constants_deletes_a_loop_known_never_to_run() {
+        // Hand-built rather than run through `compile`, since a loop
+        // this shape would already get collapsed by `optimise_loop`'s
+        // own local `set_zero`/`set_jump` passes: the point here is
+        // `fold_constants` tracking a cell's value *across* unrelated
+        // instructions at other offsets, which those passes can't do.
+        let code = vec![
+            Instr { opcode: Op::Set, arg: 0, off: 0 },   // 0: cell[0] = 0
+            Instr { opcode: Op::Right, arg: 1, off: 0 }, // 1
+            Instr { opcode: Op::Add, arg: 5, off: 0 },   // 2: cell[1] += 5, unrelated
+            Instr { opcode: Op::Left, arg: 1, off: 0 },  // 3: back to cell 0
+            Instr { opcode: Op::JZ, arg: 0, off: 2 },    // 4: known zero, never runs
+            Instr { opcode: Op::Add, arg: 9, off: 0 },   // 5: dead loop body
+            Instr { opcode: Op::JNZ, arg: 0, off: -2 },  // 6
+            Instr { opcode: Op::Add, arg: 1, off: 0 },   // 7: cell[0] += 1
+        ];
+        let folded = fold_constants(code, opts());
+        assert_eq!(
+            folded,
+            vec![
+                Instr { opcode: Op::Set, arg: 0, off: 0 },
+                Instr { opcode: Op::Right, arg: 1, off: 0 },
+                Instr { opcode: Op::Add, arg: 5, off: 0 },
+                Instr { opcode: Op::Left, arg: 1, off: 0 },
+                Instr { opcode: Op::Set, arg: 1, off: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn tape_grows_instead_of_erroring_when_configured() {
+        let mut tape: Tape<u8> = Tape::new(MemoryConfig {
+            grow_right: true,
+            grow_left: true,
+            ..MemoryConfig::default()
+        });
+        assert!(tape.cell_mut(40_000).is_ok());
+        assert!(tape.cell_mut(-5).is_ok());
+    }
+
+    #[test]
+    fn tape_reports_overrun_when_not_growable() {
+        let mut tape: Tape<u8> = Tape::new(MemoryConfig::default());
+        assert!(tape.cell_mut(40_000).is_err());
+        assert!(tape.cell_mut(-1).is_err());
+    }
+
+    #[test]
+    fn cell_width_wraps_at_its_own_boundary() {
+        assert_eq!(250u8.wrapping_add(10), 4u8);
+        assert_eq!(u16::MAX.wrapping_add(2), 1u16);
+        assert_eq!(u32::MAX.wrapping_add(2), 1u32);
     }
 }
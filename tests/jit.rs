@@ -0,0 +1,58 @@
+// Integration tests for the JIT backend (`src/jit.rs`), driven
+// through the `bfo` binary rather than `jit::run_jit` directly: the
+// backend's only observable effects are what it prints and how it
+// exits, so a subprocess comparison against the interpreter is the
+// simplest way to pin down its behaviour without reaching into
+// Cranelift internals.
+
+use std::io::Write;
+use std::process::{Command, Output};
+
+fn bfo(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bfo"))
+        .args(args)
+        .output()
+        .expect("failed to run bfo")
+}
+
+fn write_program(name: &str, src: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("bfo_jit_test_{}_{}.bf", std::process::id(), name));
+    let mut f = std::fs::File::create(&path).expect("failed to create temp .bf file");
+    f.write_all(src.as_bytes()).unwrap();
+    path
+}
+
+#[test]
+fn jit_and_interpreter_agree_on_a_copy_multiply_loop() {
+    // cell[0] = 8; cell[1] += 8 * cell[0] while cell[0] != 0, so
+    // cell[1] ends at 64 ('@'); then +. bumps and prints it as 'A'.
+    let path = write_program("copy_multiply", "++++++++[>++++++++<-]>+.");
+
+    let interp = bfo(&[path.to_str().unwrap()]);
+    let jit = bfo(&["--jit", path.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&path);
+
+    assert!(interp.status.success());
+    assert!(jit.status.success());
+    assert_eq!(interp.stdout, jit.stdout);
+    assert_eq!(jit.stdout, b"A");
+}
+
+#[test]
+fn jit_reports_out_of_bounds_instead_of_running_off_the_tape() {
+    // Walk the pointer well past the JIT's fixed 30000-cell tape
+    // before ever dereferencing it, so a missing bounds guard would
+    // show up as a crash or silent corruption rather than this
+    // error.
+    let src = format!("{}.", ">".repeat(40_000));
+    let path = write_program("oob", &src);
+
+    let jit = bfo(&["--jit", path.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&path);
+
+    assert!(jit.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&jit.stdout).trim_end(),
+        "ERROR: pointer ran off the end of the tape."
+    );
+}